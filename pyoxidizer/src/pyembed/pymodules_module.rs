@@ -3,64 +3,444 @@
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
 /// This module defines the _pymodules Python module, which exposes
-/// .py/.pyc source/code data so it can be used by an in-memory importer.
-use std::collections::{HashMap, HashSet};
-use std::io::Cursor;
+/// .py/.pyc source/code data and package resource (data file) bytes so
+/// they can be used by an in-memory importer and resource reader.
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::{Cursor, Read};
+use std::sync::Arc;
 
 use byteorder::{LittleEndian, ReadBytesExt};
 use cpython::exc::{KeyError, ValueError};
+use flate2::read::ZlibDecoder;
 use cpython::{
-    py_class, py_class_impl, py_coerce_item, PyBool, PyErr, PyModule, PyObject, PyResult, PyString,
-    Python, PythonObject, ToPyObject,
+    py_class, py_class_impl, py_coerce_item, PyBool, PyBytes, PyErr, PyList, PyModule, PyObject,
+    PyResult, PyString, Python, PythonObject, ToPyObject,
 };
 use python3_sys as pyffi;
 use python3_sys::{PyBUF_READ, PyMemoryView_FromMemory};
 
-use super::data::{PYC_MODULES_DATA, PY_MODULES_DATA};
+use super::data::{MODULES_DATA, PY_RESOURCES_DATA};
 use super::pyinterp::PYMODULES_NAME;
 
-/// Parse modules blob data into a map of module name to module data.
-fn parse_modules_blob(data: &'static [u8]) -> Result<HashMap<&str, &[u8]>, &'static str> {
-    if data.len() < 4 {
+/// Sanity value opening the header of a version 1 modules blob, ahead of
+/// the CPython magic number; version 0 (headerless) blobs do not carry it.
+const PYC_HEADER_FORMAT_MAGIC: u32 = 0x7079_6330; // "pyc0"
+
+/// Bit in a module entry's flags byte marking the module as a package.
+const ENTRY_FLAG_IS_PACKAGE: u8 = 0x1;
+
+/// Codec values for the leading byte of a modules blob, indicating how
+/// each entry's source/code payload bytes are compressed.
+const CODEC_RAW: u8 = 0;
+const CODEC_ZLIB: u8 = 1;
+const CODEC_ZSTD: u8 = 2;
+
+/// Hard cap on a single entry's decompressed payload. `register_modules`
+/// lets the codec and compressed bytes both be attacker-controlled, so a
+/// small registered blob must not be able to claim an unbounded
+/// decompressed size (a classic decompression bomb) — especially since
+/// the result is cached forever in `decompressed_source`/`decompressed_code`.
+const MAX_DECOMPRESSED_PAYLOAD_SIZE: u64 = 256 * 1024 * 1024; // 256 MiB
+
+/// Decompress a single entry's payload bytes according to `codec`,
+/// refusing to produce more than `MAX_DECOMPRESSED_PAYLOAD_SIZE` bytes.
+fn decompress_payload(codec: u8, compressed: &[u8]) -> Result<Vec<u8>, &'static str> {
+    let mut out = Vec::new();
+
+    match codec {
+        CODEC_ZLIB => {
+            let decoder = ZlibDecoder::new(compressed);
+            decoder
+                .take(MAX_DECOMPRESSED_PAYLOAD_SIZE + 1)
+                .read_to_end(&mut out)
+                .map_err(|_| "zlib decompression of module payload failed")?;
+        }
+        CODEC_ZSTD => {
+            let decoder = zstd::stream::read::Decoder::new(compressed)
+                .map_err(|_| "zstd decompression of module payload failed")?;
+            decoder
+                .take(MAX_DECOMPRESSED_PAYLOAD_SIZE + 1)
+                .read_to_end(&mut out)
+                .map_err(|_| "zstd decompression of module payload failed")?;
+        }
+        _ => return Err("unknown modules blob codec"),
+    }
+
+    if out.len() as u64 > MAX_DECOMPRESSED_PAYLOAD_SIZE {
+        return Err("decompressed module payload exceeds the maximum allowed size");
+    }
+
+    Ok(out)
+}
+
+/// One module's record in a `ModuleBlob`: whether it's a package, and
+/// byte ranges (absolute offsets into the blob's `data`) for its source
+/// and/or compiled bytecode, either of which may be absent.
+struct ModuleEntry {
+    is_package: bool,
+    source: Option<(usize, usize)>,
+    code: Option<(usize, usize)>,
+}
+
+/// Parse the unified modules index: a u32 entry count, followed per-entry
+/// by `(u32 name_length, u8 flags, u32 source_length, u32 code_length)`
+/// records (flags bit0 = is_package), followed by all names concatenated,
+/// then all source payloads concatenated, then all code payloads
+/// concatenated. A zero length means that entry has no source (resp.
+/// code).
+///
+/// Ranges are absolute offsets into `data`, rooted at `base`, so they
+/// remain valid regardless of where the index itself lives. Returns the
+/// index plus the offset immediately following the parsed region.
+fn parse_module_entries(
+    data: &[u8],
+    base: usize,
+) -> Result<(HashMap<String, ModuleEntry>, usize), &'static str> {
+    if data.len() < base + 4 {
         return Err("modules data too small");
     }
 
-    let mut reader = Cursor::new(data);
+    let mut reader = Cursor::new(&data[base..]);
+
+    let count = reader
+        .read_u32::<LittleEndian>()
+        .map_err(|_| "modules data truncated while reading entry count")?;
+
+    // Each entry's fixed-size record is 13 bytes (name_length + flags +
+    // source_length + code_length). Bound the claimed `count` against how
+    // many such records could possibly fit in the remaining buffer before
+    // trusting it for `Vec::with_capacity` or as a loop bound — `data` may
+    // come from `register_modules`, which is untrusted input.
+    const ENTRY_RECORD_SIZE: usize = 13;
+    let remaining = data.len() - base - 4;
+    if count as usize > remaining / ENTRY_RECORD_SIZE {
+        return Err("modules data entry count exceeds remaining buffer size");
+    }
 
-    let count = reader.read_u32::<LittleEndian>().unwrap();
-    let mut index = Vec::with_capacity(count as usize);
-    let mut total_names_length = 0;
+    let mut raw = Vec::with_capacity(count as usize);
+    let mut total_names_length: usize = 0;
+    let mut total_source_length: usize = 0;
+    let mut total_code_length: usize = 0;
 
     let mut i = 0;
     while i < count {
-        let name_length = reader.read_u32::<LittleEndian>().unwrap() as usize;
-        let data_length = reader.read_u32::<LittleEndian>().unwrap() as usize;
-
-        index.push((name_length, data_length));
-        total_names_length = total_names_length + name_length;
+        let name_length = reader
+            .read_u32::<LittleEndian>()
+            .map_err(|_| "modules data truncated while reading entry header")? as usize;
+        let flags = reader
+            .read_u8()
+            .map_err(|_| "modules data truncated while reading entry header")?;
+        let source_length = reader
+            .read_u32::<LittleEndian>()
+            .map_err(|_| "modules data truncated while reading entry header")? as usize;
+        let code_length = reader
+            .read_u32::<LittleEndian>()
+            .map_err(|_| "modules data truncated while reading entry header")? as usize;
+
+        total_names_length = total_names_length
+            .checked_add(name_length)
+            .ok_or("modules data name length overflow")?;
+        total_source_length = total_source_length
+            .checked_add(source_length)
+            .ok_or("modules data source length overflow")?;
+        total_code_length = total_code_length
+            .checked_add(code_length)
+            .ok_or("modules data code length overflow")?;
+        raw.push((name_length, flags, source_length, code_length));
         i = i + 1;
     }
 
+    let names_start = base + reader.position() as usize;
+    let sources_start = names_start
+        .checked_add(total_names_length)
+        .ok_or("modules data offset overflow")?;
+    let codes_start = sources_start
+        .checked_add(total_source_length)
+        .ok_or("modules data offset overflow")?;
+    let codes_end = codes_start
+        .checked_add(total_code_length)
+        .ok_or("modules data offset overflow")?;
+
+    if codes_end > data.len() {
+        return Err("modules data truncated: payload shorter than declared lengths");
+    }
+
+    let mut names_offset = names_start;
+    let mut source_offset = sources_start;
+    let mut code_offset = codes_start;
     let mut res = HashMap::with_capacity(count as usize);
-    let values_start_offset = reader.position() as usize + total_names_length;
+
+    for (name_length, flags, source_length, code_length) in raw {
+        let name = std::str::from_utf8(&data[names_offset..names_offset + name_length])
+            .map_err(|_| "modules data contains a non-UTF-8 module name")?
+            .to_string();
+        names_offset = names_offset + name_length;
+
+        let source = if source_length > 0 {
+            Some((source_offset, source_offset + source_length))
+        } else {
+            None
+        };
+        source_offset = source_offset + source_length;
+
+        let code = if code_length > 0 {
+            Some((code_offset, code_offset + code_length))
+        } else {
+            None
+        };
+        code_offset = code_offset + code_length;
+
+        res.insert(
+            name,
+            ModuleEntry {
+                is_package: flags & ENTRY_FLAG_IS_PACKAGE != 0,
+                source,
+                code,
+            },
+        );
+    }
+
+    Ok((res, code_offset))
+}
+
+/// Parse a two-level resources blob into a map of package to a map of
+/// resource name to resource data.
+///
+/// The layout mirrors the modules blob, but with an extra level of
+/// indexing: a package's resources are themselves a name/data index.
+fn parse_resources_blob(
+    data: &'static [u8],
+) -> Result<HashMap<&'static str, HashMap<&'static str, &'static [u8]>>, &'static str> {
+    if data.len() < 4 {
+        return Err("resources data too small");
+    }
+
+    let mut reader = Cursor::new(data);
+
+    let package_count = reader
+        .read_u32::<LittleEndian>()
+        .map_err(|_| "resources data truncated while reading package count")?;
+
+    // Each package record is at least 8 bytes (name_length + resource_count);
+    // bound the claimed count against how many could possibly fit in the
+    // buffer before trusting it for `Vec::with_capacity`/as a loop bound.
+    const PACKAGE_RECORD_MIN_SIZE: usize = 8;
+    let remaining = data.len() - 4;
+    if package_count as usize > remaining / PACKAGE_RECORD_MIN_SIZE {
+        return Err("resources data package count exceeds remaining buffer size");
+    }
+
+    let mut package_index = Vec::with_capacity(package_count as usize);
+    let mut total_names_length: usize = 0;
+    let mut total_resources_length: usize = 0;
+
+    let mut i = 0;
+    while i < package_count {
+        let name_length = reader
+            .read_u32::<LittleEndian>()
+            .map_err(|_| "resources data truncated while reading package header")? as usize;
+        let resource_count = reader
+            .read_u32::<LittleEndian>()
+            .map_err(|_| "resources data truncated while reading package header")?;
+
+        const RESOURCE_RECORD_SIZE: usize = 8;
+        let package_remaining = data.len().saturating_sub(reader.position() as usize);
+        if resource_count as usize > package_remaining / RESOURCE_RECORD_SIZE {
+            return Err("resources data resource count exceeds remaining buffer size");
+        }
+
+        let mut resource_index = Vec::with_capacity(resource_count as usize);
+        let mut j = 0;
+        while j < resource_count {
+            let resource_name_length = reader
+                .read_u32::<LittleEndian>()
+                .map_err(|_| "resources data truncated while reading resource header")? as usize;
+            let resource_data_length = reader
+                .read_u32::<LittleEndian>()
+                .map_err(|_| "resources data truncated while reading resource header")? as usize;
+            resource_index.push((resource_name_length, resource_data_length));
+            total_names_length = total_names_length
+                .checked_add(resource_name_length)
+                .ok_or("resources data name length overflow")?;
+            total_resources_length = total_resources_length
+                .checked_add(resource_data_length)
+                .ok_or("resources data resource length overflow")?;
+            j = j + 1;
+        }
+
+        total_names_length = total_names_length
+            .checked_add(name_length)
+            .ok_or("resources data name length overflow")?;
+        package_index.push((name_length, resource_index));
+        i = i + 1;
+    }
+
+    let names_start = reader.position() as usize;
+    let values_start_offset = names_start
+        .checked_add(total_names_length)
+        .ok_or("resources data offset overflow")?;
+    let values_end_offset = values_start_offset
+        .checked_add(total_resources_length)
+        .ok_or("resources data offset overflow")?;
+
+    if values_end_offset > data.len() {
+        return Err("resources data truncated: payload shorter than declared lengths");
+    }
+
+    let mut res = HashMap::with_capacity(package_count as usize);
+    let mut names_offset = names_start;
     let mut values_current_offset: usize = 0;
 
-    for (name_length, value_length) in index {
-        let offset = reader.position() as usize;
+    for (name_length, resource_index) in package_index {
+        let name = std::str::from_utf8(&data[names_offset..names_offset + name_length])
+            .map_err(|_| "resources data contains a non-UTF-8 package name")?;
+        names_offset = names_offset + name_length;
+
+        let mut resources = HashMap::with_capacity(resource_index.len());
 
-        let name = unsafe { std::str::from_utf8_unchecked(&data[offset..offset + name_length]) };
+        for (resource_name_length, resource_data_length) in resource_index {
+            let resource_name =
+                std::str::from_utf8(&data[names_offset..names_offset + resource_name_length])
+                    .map_err(|_| "resources data contains a non-UTF-8 resource name")?;
+            names_offset = names_offset + resource_name_length;
 
-        let value_offset = values_start_offset + values_current_offset;
-        let value = &data[value_offset..value_offset + value_length];
-        reader.set_position(offset as u64 + name_length as u64);
-        values_current_offset = values_current_offset + value_length;
+            let value_offset = values_start_offset + values_current_offset;
+            let value = &data[value_offset..value_offset + resource_data_length];
+            values_current_offset = values_current_offset + resource_data_length;
+
+            resources.insert(resource_name, value);
+        }
 
-        res.insert(name, value);
+        res.insert(name, resources);
     }
 
     Ok(res)
 }
 
+/// A parsed modules blob, owning the bytes its entries index into.
+///
+/// Unlike the build-time embedded blob, a registered blob's bytes aren't
+/// `'static`: they come from a Python `bytes` object handed to
+/// `register_modules` at runtime. Rather than borrow slices out of it
+/// (which would tie the blob's lifetime to that call), we keep the bytes
+/// alive for the life of the process in an `Arc` and index into them with
+/// byte ranges.
+///
+/// Each entry's source/code range holds bytes compressed with `codec`
+/// (`CODEC_RAW` meaning not compressed at all). Compressed payloads are
+/// decompressed lazily the first time a module is requested and cached in
+/// `decompressed_source`/`decompressed_code`, so repeat lookups and the
+/// uncompressed fast path both return a stable pointer to back a
+/// `memoryview` with.
+struct ModuleBlob {
+    data: Arc<[u8]>,
+    codec: u8,
+    entries: HashMap<String, ModuleEntry>,
+    decompressed_source: RefCell<HashMap<String, Vec<u8>>>,
+    decompressed_code: RefCell<HashMap<String, Vec<u8>>>,
+}
+
+/// Resolve a module's payload bytes to a stable `(pointer, length)`,
+/// transparently decompressing and caching on first access.
+fn resolve_payload(
+    blob: &ModuleBlob,
+    name: &str,
+    range: (usize, usize),
+    cache: &RefCell<HashMap<String, Vec<u8>>>,
+) -> Result<(*const u8, usize), &'static str> {
+    let (start, end) = range;
+
+    if blob.codec == CODEC_RAW {
+        let slice = &blob.data[start..end];
+        return Ok((slice.as_ptr(), slice.len()));
+    }
+
+    if !cache.borrow().contains_key(name) {
+        let decompressed = decompress_payload(blob.codec, &blob.data[start..end])?;
+        cache.borrow_mut().insert(name.to_string(), decompressed);
+    }
+
+    let cache_ref = cache.borrow();
+    let value = cache_ref.get(name).unwrap();
+    Ok((value.as_ptr(), value.len()))
+}
+
+/// Parse a modules blob: a leading codec byte (see `CODEC_RAW` et al),
+/// then a version byte and an optional CPython magic number header,
+/// followed by the unified module entries.
+///
+/// A version of 0 means the blob is headerless (no bytecode, or bytecode
+/// whose compatibility isn't tracked). A version of 1 means the blob is
+/// followed by an 8-byte header: a `PYC_HEADER_FORMAT_MAGIC` sanity value
+/// and the CPython `pyc` magic number the embedded bytecode was compiled
+/// with, both little-endian u32s. The stored magic number, if present, is
+/// returned so callers can validate it against the running interpreter.
+fn parse_module_blob(data: Vec<u8>) -> Result<(ModuleBlob, Option<u32>), &'static str> {
+    if data.is_empty() {
+        return Err("modules data too small");
+    }
+
+    let codec = data[0];
+    if codec != CODEC_RAW && codec != CODEC_ZLIB && codec != CODEC_ZSTD {
+        return Err("unknown modules blob codec");
+    }
+
+    if data.len() < 2 {
+        return Err("modules data too small");
+    }
+
+    let (index_base, pyc_magic) = match data[1] {
+        0 => (2, None),
+        1 => {
+            if data.len() < 10 {
+                return Err("modules data too small for pyc header");
+            }
+
+            let mut header = Cursor::new(&data[2..10]);
+            let format_magic = header.read_u32::<LittleEndian>().unwrap();
+            if format_magic != PYC_HEADER_FORMAT_MAGIC {
+                return Err("unrecognized modules blob header");
+            }
+            let pyc_magic = header.read_u32::<LittleEndian>().unwrap();
+
+            (10, Some(pyc_magic))
+        }
+        _ => return Err("unknown modules blob version"),
+    };
+
+    let (entries, _) = parse_module_entries(&data, index_base)?;
+
+    Ok((
+        ModuleBlob {
+            data: Arc::from(data),
+            codec,
+            entries,
+            decompressed_source: RefCell::new(HashMap::new()),
+            decompressed_code: RefCell::new(HashMap::new()),
+        },
+        pyc_magic,
+    ))
+}
+
+/// Validate a stored CPython `pyc` magic number against the running
+/// interpreter, returning a descriptive `ValueError` on mismatch.
+fn validate_pyc_magic(py: Python, stored_magic: u32) -> PyResult<()> {
+    let interpreter_magic = unsafe { pyffi::PyImport_GetMagicNumber() } as u32;
+
+    if stored_magic != interpreter_magic {
+        return Err(PyErr::new::<ValueError, _>(
+            py,
+            format!(
+                "embedded bytecode was compiled with magic number {}, but this interpreter expects {}; rebuild with a matching Python version",
+                stored_magic, interpreter_magic
+            ),
+        ));
+    }
+
+    Ok(())
+}
+
 #[allow(unused_doc_comments)]
 /// Python type to facilitate access to in-memory modules data.
 ///
@@ -69,52 +449,77 @@ fn parse_modules_blob(data: &'static [u8]) -> Result<HashMap<&str, &[u8]>, &'sta
 /// we'd need to allocate PyObject instances for every value. This adds
 /// overhead to startup. This type minimizes PyObject instantiation to
 /// reduce overhead.
+///
+/// `blobs` holds the build-time blob at index 0 followed by any blobs
+/// registered at runtime via `register_modules`, in registration order.
+/// Lookups scan from the end, so a later registration shadows an earlier
+/// one (or the built-in blob) that defines the same module name,
+/// including its `is_package` flag.
 py_class!(class ModulesType |py| {
-    data py_modules: HashMap<&'static str, &'static [u8]>;
-    data pyc_modules: HashMap<&'static str, &'static [u8]>;
-    data packages: HashSet<&'static str>;
+    data blobs: RefCell<Vec<ModuleBlob>>;
+    data resources: HashMap<&'static str, HashMap<&'static str, &'static [u8]>>;
 
     def get_source(&self, name: PyString) -> PyResult<PyObject> {
         let key = name.to_string(py)?;
 
-        return match self.py_modules(py).get(&*key) {
-            Some(value) => {
-                let py_value = unsafe {
-                    let ptr = PyMemoryView_FromMemory(value.as_ptr() as * mut i8, value.len() as isize, PyBUF_READ);
-                    PyObject::from_owned_ptr_opt(py, ptr)
-                }.unwrap();
-
-                Ok(py_value)
-            },
-            None => Err(PyErr::new::<KeyError, _>(py, "module not available"))
+        for blob in self.blobs(py).borrow().iter().rev() {
+            if let Some(entry) = blob.entries.get(&*key) {
+                return match entry.source {
+                    Some(range) => {
+                        let (ptr, len) = match resolve_payload(blob, &key, range, &blob.decompressed_source) {
+                            Ok(value) => value,
+                            Err(msg) => return Err(PyErr::new::<ValueError, _>(py, msg)),
+                        };
+
+                        let py_value = unsafe {
+                            let ptr = PyMemoryView_FromMemory(ptr as * mut i8, len as isize, PyBUF_READ);
+                            PyObject::from_owned_ptr_opt(py, ptr)
+                        }.unwrap();
+
+                        Ok(py_value)
+                    },
+                    None => Err(PyErr::new::<KeyError, _>(py, "module not available")),
+                };
+            }
         }
+
+        Err(PyErr::new::<KeyError, _>(py, "module not available"))
     }
 
     def get_code(&self, name: PyString) -> PyResult<PyObject> {
         let key = name.to_string(py)?;
 
-        return match self.pyc_modules(py).get(&*key) {
-            Some(value) => {
-                let py_value = unsafe {
-                    let ptr = PyMemoryView_FromMemory(value.as_ptr() as * mut i8, value.len() as isize, PyBUF_READ);
-                    PyObject::from_owned_ptr_opt(py, ptr)
-                }.unwrap();
-
-                Ok(py_value)
-            },
-            None => Err(PyErr::new::<KeyError, _>(py, "module not available"))
+        for blob in self.blobs(py).borrow().iter().rev() {
+            if let Some(entry) = blob.entries.get(&*key) {
+                return match entry.code {
+                    Some(range) => {
+                        let (ptr, len) = match resolve_payload(blob, &key, range, &blob.decompressed_code) {
+                            Ok(value) => value,
+                            Err(msg) => return Err(PyErr::new::<ValueError, _>(py, msg)),
+                        };
+
+                        let py_value = unsafe {
+                            let ptr = PyMemoryView_FromMemory(ptr as * mut i8, len as isize, PyBUF_READ);
+                            PyObject::from_owned_ptr_opt(py, ptr)
+                        }.unwrap();
+
+                        Ok(py_value)
+                    },
+                    None => Err(PyErr::new::<KeyError, _>(py, "module not available")),
+                };
+            }
         }
+
+        Err(PyErr::new::<KeyError, _>(py, "module not available"))
     }
 
     def has_module(&self, name: PyString) -> PyResult<PyBool> {
         let key = name.to_string(py)?;
 
-        if self.py_modules(py).contains_key(&*key) {
-            return Ok(true.to_py_object(py));
-        }
-
-        if self.pyc_modules(py).contains_key(&*key) {
-            return Ok(true.to_py_object(py));
+        for blob in self.blobs(py).borrow().iter() {
+            if blob.entries.contains_key(&key) {
+                return Ok(true.to_py_object(py));
+            }
         }
 
         return Ok(false.to_py_object(py));
@@ -123,51 +528,99 @@ py_class!(class ModulesType |py| {
     def is_package(&self, name: PyString) -> PyResult<PyBool> {
         let key = name.to_string(py)?;
 
-        Ok(match self.packages(py).contains(&*key) {
-            true => true.to_py_object(py),
-            false => false.to_py_object(py),
+        for blob in self.blobs(py).borrow().iter().rev() {
+            if let Some(entry) = blob.entries.get(&key) {
+                return Ok(entry.is_package.to_py_object(py));
+            }
+        }
+
+        Ok(false.to_py_object(py))
+    }
+
+    def get_resource(&self, package: PyString, name: PyString) -> PyResult<PyObject> {
+        let package_key = package.to_string(py)?;
+        let name_key = name.to_string(py)?;
+
+        return match self.resources(py).get(&*package_key) {
+            Some(resources) => match resources.get(&*name_key) {
+                Some(value) => {
+                    let py_value = unsafe {
+                        let ptr = PyMemoryView_FromMemory(value.as_ptr() as * mut i8, value.len() as isize, PyBUF_READ);
+                        PyObject::from_owned_ptr_opt(py, ptr)
+                    }.unwrap();
+
+                    Ok(py_value)
+                },
+                None => Err(PyErr::new::<KeyError, _>(py, "resource not available")),
+            },
+            None => Err(PyErr::new::<KeyError, _>(py, "resource not available"))
+        }
+    }
+
+    def has_resource(&self, package: PyString, name: PyString) -> PyResult<PyBool> {
+        let package_key = package.to_string(py)?;
+        let name_key = name.to_string(py)?;
+
+        Ok(match self.resources(py).get(&*package_key) {
+            Some(resources) => resources.contains_key(&*name_key).to_py_object(py),
+            None => false.to_py_object(py),
         })
     }
-});
 
-fn populate_packages(packages: &mut HashSet<&'static str>, name: &'static str) {
-    let mut search = name;
+    def iter_resources(&self, package: PyString) -> PyResult<PyList> {
+        let package_key = package.to_string(py)?;
 
-    loop {
-        match search.rfind(".") {
-            Some(idx) => {
-                packages.insert(&search[0..idx]);
-                search = &search[0..idx];
-            }
-            None => break,
+        let names: Vec<PyObject> = match self.resources(py).get(&*package_key) {
+            Some(resources) => resources.keys().map(|name| name.to_py_object(py).into_object()).collect(),
+            None => Vec::new(),
         };
+
+        Ok(PyList::new(py, &names))
     }
-}
+
+    /// Parse and register an additional packed module set at runtime.
+    ///
+    /// `data` uses the same wire format `make_modules` parses at
+    /// startup (see `parse_module_blob`). Names defined in `data` take
+    /// precedence over identically-named modules from earlier
+    /// registrations or the build-time blob, including their
+    /// `is_package` flag, letting plugin/overlay module sets shadow
+    /// what's already embedded.
+    def register_modules(&self, data: PyBytes) -> PyResult<PyObject> {
+        let bytes = data.data(py).to_vec();
+
+        let (blob, pyc_magic) = match parse_module_blob(bytes) {
+            Ok(value) => value,
+            Err(msg) => return Err(PyErr::new::<ValueError, _>(py, msg)),
+        };
+
+        if let Some(stored_magic) = pyc_magic {
+            validate_pyc_magic(py, stored_magic)?;
+        }
+
+        self.blobs(py).borrow_mut().push(blob);
+
+        Ok(py.None())
+    }
+});
 
 /// Construct the global ModulesType instance from an embedded data structure.
 fn make_modules(py: Python) -> PyResult<ModulesType> {
-    let py_modules = match parse_modules_blob(PY_MODULES_DATA) {
+    let (blob, pyc_magic) = match parse_module_blob(MODULES_DATA.to_vec()) {
         Ok(value) => value,
         Err(msg) => return Err(PyErr::new::<ValueError, _>(py, msg)),
     };
 
-    let pyc_modules = match parse_modules_blob(PYC_MODULES_DATA) {
+    if let Some(stored_magic) = pyc_magic {
+        validate_pyc_magic(py, stored_magic)?;
+    }
+
+    let resources = match parse_resources_blob(PY_RESOURCES_DATA) {
         Ok(value) => value,
         Err(msg) => return Err(PyErr::new::<ValueError, _>(py, msg)),
     };
 
-    // TODO consider baking set of packages into embedded data.
-    let mut packages: HashSet<&'static str> = HashSet::with_capacity(pyc_modules.len());
-
-    for key in py_modules.keys() {
-        populate_packages(&mut packages, key);
-    }
-
-    for key in pyc_modules.keys() {
-        populate_packages(&mut packages, key);
-    }
-
-    ModulesType::create_instance(py, py_modules, pyc_modules, packages)
+    ModulesType::create_instance(py, RefCell::new(vec![blob]), resources)
 }
 
 const DOC: &'static [u8] = b"Binary representation of Python modules\0";
@@ -224,3 +677,99 @@ pub unsafe extern "C" fn PyInit__pymodules() -> *mut pyffi::PyObject {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::ZlibEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+
+    #[test]
+    fn decompress_payload_rejects_unknown_codec() {
+        let err = decompress_payload(0xff, &[]).unwrap_err();
+        assert_eq!(err, "unknown modules blob codec");
+    }
+
+    #[test]
+    fn decompress_payload_accepts_small_zlib_payload() {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(b"hello world").unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        let out = decompress_payload(CODEC_ZLIB, &compressed).unwrap();
+        assert_eq!(out, b"hello world");
+    }
+
+    #[test]
+    fn decompress_payload_rejects_zlib_decompression_bomb() {
+        // A highly compressible payload that decompresses past the cap.
+        // Zeros compress to a tiny fraction of their size, so this keeps
+        // the test fast despite the declared output being over the limit.
+        let oversized = vec![0u8; MAX_DECOMPRESSED_PAYLOAD_SIZE as usize + 1024];
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::best());
+        encoder.write_all(&oversized).unwrap();
+        let compressed = encoder.finish().unwrap();
+        drop(oversized);
+
+        let err = decompress_payload(CODEC_ZLIB, &compressed).unwrap_err();
+        assert_eq!(
+            err,
+            "decompressed module payload exceeds the maximum allowed size"
+        );
+    }
+
+    #[test]
+    fn parse_module_entries_rejects_oversized_count() {
+        // Declares an entry count far larger than could possibly fit in
+        // the remaining 0 bytes of buffer.
+        let data = 0xffff_ffffu32.to_le_bytes().to_vec();
+        let err = parse_module_entries(&data, 0).unwrap_err();
+        assert_eq!(err, "modules data entry count exceeds remaining buffer size");
+    }
+
+    #[test]
+    fn parse_module_entries_rejects_truncated_buffer() {
+        let mut data = Vec::new();
+        data.extend_from_slice(&1u32.to_le_bytes()); // count = 1
+        data.extend_from_slice(&3u32.to_le_bytes()); // name_length = 3
+        data.push(0); // flags
+                      // Missing source_length, code_length, and the name bytes themselves.
+
+        let err = parse_module_entries(&data, 0).unwrap_err();
+        assert_eq!(err, "modules data truncated while reading entry header");
+    }
+
+    #[test]
+    fn parse_module_entries_rejects_non_utf8_name() {
+        let mut data = Vec::new();
+        data.extend_from_slice(&1u32.to_le_bytes()); // count = 1
+        data.extend_from_slice(&2u32.to_le_bytes()); // name_length = 2
+        data.push(0); // flags
+        data.extend_from_slice(&0u32.to_le_bytes()); // source_length = 0
+        data.extend_from_slice(&0u32.to_le_bytes()); // code_length = 0
+        data.extend_from_slice(&[0xff, 0xfe]); // invalid UTF-8 name
+
+        let err = parse_module_entries(&data, 0).unwrap_err();
+        assert_eq!(err, "modules data contains a non-UTF-8 module name");
+    }
+
+    #[test]
+    fn parse_module_entries_accepts_well_formed_entry() {
+        let mut data = Vec::new();
+        data.extend_from_slice(&1u32.to_le_bytes()); // count = 1
+        data.extend_from_slice(&3u32.to_le_bytes()); // name_length = 3
+        data.push(ENTRY_FLAG_IS_PACKAGE); // flags
+        data.extend_from_slice(&1u32.to_le_bytes()); // source_length = 1
+        data.extend_from_slice(&0u32.to_le_bytes()); // code_length = 0
+        data.extend_from_slice(b"foo"); // name
+        data.extend_from_slice(b"s"); // source payload
+
+        let (entries, end) = parse_module_entries(&data, 0).unwrap();
+        let entry = entries.get("foo").unwrap();
+        assert!(entry.is_package);
+        assert!(entry.source.is_some());
+        assert!(entry.code.is_none());
+        assert_eq!(end, data.len());
+    }
+}